@@ -1,18 +1,83 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::hash::Hash;
+use std::ops::{Add, Sub};
+
+/// Error returned by the fallible, non-panicking `try_*` mutation methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeError<T> {
+    /// The tree already contains a node equal to this one.
+    DuplicateNode(T),
+    /// The given parent is not in the tree.
+    MissingParent(T),
+    /// The given node is not in the tree.
+    NotInTree(T),
+    /// Moving this node under the given new parent would create a cycle, because the new
+    /// parent is the node itself or lies within its own subtree.
+    WouldCreateCycle(T),
+}
+
+impl<T: fmt::Debug> fmt::Display for TreeError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::DuplicateNode(obj) => write!(f, "node {:?} already exists in the tree", obj),
+            TreeError::MissingParent(parent) => write!(f, "parent {:?} is not in the tree", parent),
+            TreeError::NotInTree(obj) => write!(f, "node {:?} is not in the tree", obj),
+            TreeError::WouldCreateCycle(obj) => {
+                write!(f, "moving node {:?} under that parent would create a cycle", obj)
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for TreeError<T> {}
+
+/// Numeric type usable as a subtree-weight aggregate.
+///
+/// Blanket-implemented for any type that supports the arithmetic `MappedTree` needs to keep
+/// its cached aggregates up to date; you won't usually need to implement this by hand.
+///
+/// `PartialOrd` is required so weight decreases can be applied as a direct subtraction of two
+/// non-negative magnitudes instead of negating a delta first — the latter would overflow for
+/// unsigned weight types (e.g. `u32`, `usize`), which are otherwise perfectly natural choices.
+pub trait Weight: Copy + Default + PartialOrd + Add<Output = Self> + Sub<Output = Self> {}
+
+impl<W: Copy + Default + PartialOrd + Add<Output = W> + Sub<Output = W>> Weight for W {}
+
+/// Zero-sized weight used by trees that don't opt into weighted subtree aggregates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd)]
+pub struct NoWeight;
+
+impl Add for NoWeight {
+    type Output = NoWeight;
+
+    fn add(self, _rhs: NoWeight) -> NoWeight {
+        NoWeight
+    }
+}
+
+impl Sub for NoWeight {
+    type Output = NoWeight;
+
+    fn sub(self, _rhs: NoWeight) -> NoWeight {
+        NoWeight
+    }
+}
 
-struct Links<T: Clone> {
+struct Links<T: Clone, W: Weight> {
     parent: Option<T>,
     children: Vec<T>,
+    weight: W,
+    subtree_weight: W,
 }
 
 #[derive(Default)]
-pub struct MappedTree<T: Clone + Eq + Hash> {
-    links_by_obj: HashMap<T, Links<T>>,
+pub struct MappedTree<T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    links_by_obj: HashMap<T, Links<T, W>>,
     size: usize,
 }
 
-impl<T: Clone + Eq + Hash> MappedTree<T> {
+impl<T: Clone + Eq + Hash, W: Weight> MappedTree<T, W> {
     pub fn new() -> Self {
         MappedTree {
             links_by_obj: HashMap::new(),
@@ -31,7 +96,7 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
         self.size
     }
 
-    fn links(&self, obj: &T) -> Option<&Links<T>> {
+    fn links(&self, obj: &T) -> Option<&Links<T, W>> {
         self.links_by_obj.get(obj)
     }
 
@@ -70,10 +135,12 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
                 self.links_by_obj.get_mut(child).unwrap().parent = Some(obj.clone());
             }
 
-            self.links_by_obj.remove(&old_root);
+            let old_links = self.links_by_obj.remove(&old_root).unwrap();
             let links = Links {
                 parent: None,
-                children: children,
+                children,
+                weight: old_links.weight,
+                subtree_weight: old_links.subtree_weight,
             };
             self.links_by_obj.insert(obj.clone(), links);
             return Some(old_root);
@@ -84,6 +151,8 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
             Links {
                 parent: None,
                 children: Vec::new(),
+                weight: W::default(),
+                subtree_weight: W::default(),
             },
         );
         self.size += 1;
@@ -91,6 +160,32 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
         None
     }
 
+    /// Non-panicking version of [`MappedTree::reset_root`].
+    ///
+    /// Fails with [`TreeError::DuplicateNode`] if `obj` is already present in the tree,
+    /// since reusing it as the new root would silently corrupt the existing links.
+    pub fn try_reset_root(&mut self, obj: &T) -> Result<Option<T>, TreeError<T>> {
+        if self.contains(obj) {
+            return Err(TreeError::DuplicateNode(obj.clone()));
+        }
+
+        Ok(self.reset_root(obj))
+    }
+
+    /// Non-panicking version of [`MappedTree::insert`].
+    pub fn try_insert(&mut self, obj: &T, parent: &T) -> Result<(), TreeError<T>> {
+        if self.links_by_obj.contains_key(obj) {
+            return Err(TreeError::DuplicateNode(obj.clone()));
+        }
+
+        if !self.links_by_obj.contains_key(parent) {
+            return Err(TreeError::MissingParent(parent.clone()));
+        }
+
+        self.insert(obj, parent);
+        Ok(())
+    }
+
     pub fn insert(&mut self, obj: &T, parent: &T) {
         if self.links_by_obj.contains_key(obj) {
             panic!("mapped tree MUST contain UNIQUE elements only");
@@ -108,16 +203,78 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
             Links {
                 parent: Some(parent.clone()),
                 children: Vec::new(),
+                weight: W::default(),
+                subtree_weight: W::default(),
             },
         );
         self.size += 1;
     }
 
+    /// Inserts `obj` under `parent`, then assigns it `weight` and propagates it into the
+    /// cached subtree-weight aggregate of `obj` and all of its ancestors.
+    ///
+    /// Panics under the same conditions as [`MappedTree::insert`].
+    pub fn insert_weighted(&mut self, obj: &T, parent: &T, weight: W) {
+        self.insert(obj, parent);
+        self.set_weight(obj, weight);
+    }
+
+    /// Sets `obj`'s own weight, adjusting the cached subtree-weight aggregate of `obj` and
+    /// all of its ancestors by the difference from its previous weight.
+    ///
+    /// Does nothing if `obj` is not in the tree.
+    pub fn set_weight(&mut self, obj: &T, weight: W) {
+        let old_weight = match self.links(obj) {
+            Some(links) => links.weight,
+            None => return,
+        };
+
+        self.links_by_obj.get_mut(obj).unwrap().weight = weight;
+
+        if weight >= old_weight {
+            self.propagate_increase(obj, weight - old_weight);
+        } else {
+            self.propagate_decrease(obj, old_weight - weight);
+        }
+    }
+
+    /// Returns the cached sum of weights over `obj`'s whole subtree, including `obj` itself.
+    pub fn subtree_weight(&self, obj: &T) -> Option<W> {
+        self.links(obj).map(|links| links.subtree_weight)
+    }
+
+    /// Adds `delta` to the cached subtree-weight aggregate of `obj` and every ancestor above it.
+    fn propagate_increase(&mut self, obj: &T, delta: W) {
+        self.propagate(obj, |subtree_weight| subtree_weight + delta);
+    }
+
+    /// Subtracts `delta` from the cached subtree-weight aggregate of `obj` and every ancestor
+    /// above it.
+    ///
+    /// Implemented as a direct subtraction, rather than negating `delta` and adding it, so that
+    /// unsigned weight types never have to compute a negative intermediate value.
+    fn propagate_decrease(&mut self, obj: &T, delta: W) {
+        self.propagate(obj, |subtree_weight| subtree_weight - delta);
+    }
+
+    fn propagate(&mut self, obj: &T, op: impl Fn(W) -> W) {
+        let mut current = Some(obj.clone());
+        while let Some(obj) = current {
+            current = match self.links_by_obj.get_mut(&obj) {
+                Some(links) => {
+                    links.subtree_weight = op(links.subtree_weight);
+                    links.parent.clone()
+                }
+                None => None,
+            };
+        }
+    }
+
     fn remove_children_without_links(&mut self, obj: &T) {
         if let Some(children) = self.children(obj) {
             let children = children.clone();
             for child in &children {
-                self.remove_children(&child);
+                self.remove_children_without_links(child);
             }
 
             let children_count = children.len();
@@ -129,10 +286,18 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
     }
 
     pub fn remove_children(&mut self, obj: &T) {
+        let removed_weight = self
+            .links(obj)
+            .map(|links| links.subtree_weight - links.weight);
+
         self.remove_children_without_links(obj);
         if let Some(links) = self.links_by_obj.get_mut(obj) {
             links.children.clear();
         }
+
+        if let Some(removed_weight) = removed_weight {
+            self.propagate_decrease(obj, removed_weight);
+        }
     }
 
     pub fn remove(&mut self, obj: &T) -> bool {
@@ -140,6 +305,8 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
         if let Some(links) = self.links_by_obj.remove(obj) {
             self.size -= 1;
             if let Some(parent) = &links.parent {
+                self.propagate_decrease(parent, links.weight);
+
                 let parent_links = self.links_by_obj.get_mut(parent).unwrap();
                 let obj_index = parent_links
                     .children
@@ -157,11 +324,320 @@ impl<T: Clone + Eq + Hash> MappedTree<T> {
     pub fn contains(&self, obj: &T) -> bool {
         self.links_by_obj.contains_key(obj)
     }
+
+    /// Detaches `obj`, together with its whole subtree, from its current parent and
+    /// re-attaches it under `new_parent`.
+    ///
+    /// This only rewires `obj`'s parent link and the two affected `children` vecs — the
+    /// descendant links are left untouched. The cached subtree-weight aggregate of `obj`'s
+    /// subtree is moved from the old parent chain to the new one. Besides the sibling removal,
+    /// the cycle check and the weight update each walk an ancestor chain, so the cost grows
+    /// with the depth of `obj` and `new_parent`, not with the size of the moved subtree.
+    ///
+    /// Fails with [`TreeError::NotInTree`] if either node is missing from the tree, and with
+    /// [`TreeError::WouldCreateCycle`] if `new_parent` is `obj` itself or lies within `obj`'s
+    /// own subtree.
+    pub fn move_subtree(&mut self, obj: &T, new_parent: &T) -> Result<(), TreeError<T>> {
+        if !self.contains(obj) {
+            return Err(TreeError::NotInTree(obj.clone()));
+        }
+
+        if !self.contains(new_parent) {
+            return Err(TreeError::NotInTree(new_parent.clone()));
+        }
+
+        if new_parent == obj || self.ancestors(new_parent).any(|ancestor| ancestor == obj) {
+            return Err(TreeError::WouldCreateCycle(obj.clone()));
+        }
+
+        let old_parent = self.parent(obj).cloned();
+        if let Some(old_parent) = &old_parent {
+            if old_parent == new_parent {
+                return Ok(());
+            }
+
+            let old_parent_links = self.links_by_obj.get_mut(old_parent).unwrap();
+            let obj_index = old_parent_links
+                .children
+                .iter()
+                .position(|item| item == obj)
+                .unwrap();
+            old_parent_links.children.swap_remove(obj_index);
+        }
+
+        let moved_weight = self.subtree_weight(obj).unwrap();
+        if let Some(old_parent) = &old_parent {
+            self.propagate_decrease(old_parent, moved_weight);
+        }
+
+        self.links_by_obj
+            .get_mut(new_parent)
+            .unwrap()
+            .children
+            .push(obj.clone());
+        self.links_by_obj.get_mut(obj).unwrap().parent = Some(new_parent.clone());
+        self.propagate_increase(new_parent, moved_weight);
+
+        Ok(())
+    }
+
+    /// Iterates `root` and its descendants in depth-first, pre-order.
+    pub fn dfs_preorder(&self, root: &T) -> DfsPreorder<'_, T, W> {
+        DfsPreorder {
+            tree: self,
+            current: self.links_by_obj.get_key_value(root).map(|(k, _)| k),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Iterates `root` and its descendants in breadth-first order.
+    pub fn bfs(&self, root: &T) -> Bfs<'_, T, W> {
+        Bfs {
+            tree: self,
+            current: self.links_by_obj.get_key_value(root).map(|(k, _)| k),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Iterates `obj`'s ancestors, from its direct parent up to the root.
+    pub fn ancestors(&self, obj: &T) -> Ancestors<'_, T, W> {
+        Ancestors {
+            tree: self,
+            current: self.links_by_obj.get_key_value(obj).map(|(k, _)| k),
+        }
+    }
+
+    /// Iterates `obj`'s descendants in depth-first, pre-order, not including `obj` itself.
+    pub fn descendants(&self, obj: &T) -> Descendants<'_, T, W> {
+        let stack = match self.children(obj) {
+            Some(children) => children.iter().rev().collect(),
+            None => Vec::new(),
+        };
+
+        Descendants { tree: self, stack }
+    }
+
+    /// Iterates every node in the tree that has no children.
+    pub fn leaves(&self) -> Leaves<'_, T, W> {
+        Leaves {
+            inner: self.links_by_obj.iter(),
+        }
+    }
+
+    /// Finds the lowest common ancestor of `a` and `b`.
+    ///
+    /// Returns `None` if either node is missing from the tree, or if they belong to
+    /// disconnected parts of the tree (e.g. a forest produced by misusing `reset_root`).
+    pub fn common_ancestor(&self, a: &T, b: &T) -> Option<T> {
+        if !self.contains(a) || !self.contains(b) {
+            return None;
+        }
+
+        if a == b {
+            return Some(a.clone());
+        }
+
+        let mut a_chain: HashSet<T> = HashSet::new();
+        a_chain.insert(a.clone());
+        let mut current = a.clone();
+        while let Some(parent) = self.parent(&current) {
+            a_chain.insert(parent.clone());
+            current = parent.clone();
+        }
+
+        if a_chain.contains(b) {
+            return Some(b.clone());
+        }
+
+        let mut current = b.clone();
+        while let Some(parent) = self.parent(&current) {
+            if a_chain.contains(parent) {
+                return Some(parent.clone());
+            }
+            current = parent.clone();
+        }
+
+        None
+    }
+
+    /// Builds the route from `from` to `to`, passing through their lowest common ancestor.
+    ///
+    /// Returns `None` under the same conditions as [`MappedTree::common_ancestor`].
+    pub fn path(&self, from: &T, to: &T) -> Option<Vec<T>> {
+        let lca = self.common_ancestor(from, to)?;
+
+        let mut up = Vec::new();
+        let mut current = from.clone();
+        up.push(current.clone());
+        while current != lca {
+            current = self.parent(&current).unwrap().clone();
+            up.push(current.clone());
+        }
+
+        let mut down = Vec::new();
+        let mut current = to.clone();
+        while current != lca {
+            down.push(current.clone());
+            current = self.parent(&current).unwrap().clone();
+        }
+        down.reverse();
+
+        up.extend(down);
+        Some(up)
+    }
+}
+
+/// Depth-first, pre-order iterator produced by [`MappedTree::dfs_preorder`].
+pub struct DfsPreorder<'a, T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    tree: &'a MappedTree<T, W>,
+    current: Option<&'a T>,
+    stack: Vec<&'a T>,
+}
+
+impl<'a, T: Clone + Eq + Hash, W: Weight> Iterator for DfsPreorder<'a, T, W> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let obj = self.current.take().or_else(|| self.stack.pop())?;
+        if let Some(children) = self.tree.children(obj) {
+            for child in children.iter().rev() {
+                self.stack.push(child);
+            }
+        }
+        Some(obj)
+    }
+}
+
+/// Breadth-first iterator produced by [`MappedTree::bfs`].
+pub struct Bfs<'a, T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    tree: &'a MappedTree<T, W>,
+    current: Option<&'a T>,
+    queue: VecDeque<&'a T>,
+}
+
+impl<'a, T: Clone + Eq + Hash, W: Weight> Iterator for Bfs<'a, T, W> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let obj = self.current.take().or_else(|| self.queue.pop_front())?;
+        if let Some(children) = self.tree.children(obj) {
+            for child in children {
+                self.queue.push_back(child);
+            }
+        }
+        Some(obj)
+    }
+}
+
+/// Ancestor-chain iterator produced by [`MappedTree::ancestors`].
+pub struct Ancestors<'a, T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    tree: &'a MappedTree<T, W>,
+    current: Option<&'a T>,
+}
+
+impl<'a, T: Clone + Eq + Hash, W: Weight> Iterator for Ancestors<'a, T, W> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.current.take()?;
+        let parent = self.tree.parent(current);
+        self.current = parent;
+        parent
+    }
+}
+
+/// Descendants iterator produced by [`MappedTree::descendants`].
+pub struct Descendants<'a, T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    tree: &'a MappedTree<T, W>,
+    stack: Vec<&'a T>,
+}
+
+impl<'a, T: Clone + Eq + Hash, W: Weight> Iterator for Descendants<'a, T, W> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let obj = self.stack.pop()?;
+        if let Some(children) = self.tree.children(obj) {
+            for child in children.iter().rev() {
+                self.stack.push(child);
+            }
+        }
+        Some(obj)
+    }
+}
+
+/// Leaves iterator produced by [`MappedTree::leaves`].
+pub struct Leaves<'a, T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    inner: std::collections::hash_map::Iter<'a, T, Links<T, W>>,
+}
+
+impl<'a, T: Clone + Eq + Hash, W: Weight> Iterator for Leaves<'a, T, W> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (obj, links) in self.inner.by_ref() {
+            if links.children.is_empty() {
+                return Some(obj);
+            }
+        }
+        None
+    }
+}
+
+/// Configures a [`MappedTree`] before construction.
+///
+/// Useful when the node count is known up front, so the tree's backing `HashMap` doesn't
+/// need to rehash as nodes are inserted, and an initial root can be seeded in one step
+/// instead of the `new()` + `reset_root()` dance.
+pub struct MappedTreeBuilder<T: Clone + Eq + Hash, W: Weight = NoWeight> {
+    root: Option<T>,
+    capacity: usize,
+    _weight: std::marker::PhantomData<W>,
+}
+
+impl<T: Clone + Eq + Hash, W: Weight> MappedTreeBuilder<T, W> {
+    pub fn new() -> Self {
+        MappedTreeBuilder {
+            root: None,
+            capacity: 0,
+            _weight: std::marker::PhantomData,
+        }
+    }
+
+    /// Seeds the tree with an initial root, so `build()` produces a tree with this node
+    /// already inserted instead of an empty one.
+    pub fn with_root(mut self, obj: T) -> Self {
+        self.root = Some(obj);
+        self
+    }
+
+    /// Hints at the number of nodes the tree is expected to hold.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn build(self) -> MappedTree<T, W> {
+        let mut tree = MappedTree::with_capacity(self.capacity);
+        if let Some(root) = self.root {
+            tree.reset_root(&root);
+            if let Some(links) = tree.links_by_obj.get_mut(&root) {
+                links.children.reserve(self.capacity);
+            }
+        }
+        tree
+    }
+}
+
+impl<T: Clone + Eq + Hash, W: Weight> Default for MappedTreeBuilder<T, W> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::MappedTree;
+    use super::{MappedTree, MappedTreeBuilder, TreeError};
 
     fn test_tree() -> MappedTree<i32> {
         let mut map = MappedTree::new();
@@ -205,7 +681,7 @@ mod tests {
     #[test]
     fn remove_children() {
         let mut tree = test_tree();
-        
+
         tree.remove_children(&2);
         assert!(!tree.contains(&5));
         assert!(!tree.contains(&6));
@@ -213,13 +689,232 @@ mod tests {
         assert_eq!(tree.children(&2).unwrap().len(), 0);
     }
 
+    #[test]
     fn contains() {
-        let mut tree = test_tree();
-        
+        let tree = test_tree();
+
         assert!(tree.contains(&0));
         assert!(tree.contains(&1));
         assert!(tree.contains(&2));
         assert!(tree.contains(&6));
         assert!(tree.contains(&7));
     }
+
+    #[test]
+    fn dfs_preorder() {
+        let tree = test_tree();
+        let visited: Vec<_> = tree.dfs_preorder(&0).copied().collect();
+        assert_eq!(visited, vec![0, 1, 3, 4, 2, 5, 6, 7]);
+    }
+
+    #[test]
+    fn bfs() {
+        let tree = test_tree();
+        let visited: Vec<_> = tree.bfs(&0).copied().collect();
+        assert_eq!(visited, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn ancestors() {
+        let tree = test_tree();
+        let visited: Vec<_> = tree.ancestors(&3).copied().collect();
+        assert_eq!(visited, vec![1, 0]);
+        assert_eq!(tree.ancestors(&0).count(), 0);
+    }
+
+    #[test]
+    fn descendants() {
+        let tree = test_tree();
+        let visited: Vec<_> = tree.descendants(&2).copied().collect();
+        assert_eq!(visited, vec![5, 6, 7]);
+        assert_eq!(tree.descendants(&6).count(), 0);
+    }
+
+    #[test]
+    fn leaves() {
+        let tree = test_tree();
+        let mut visited: Vec<_> = tree.leaves().copied().collect();
+        visited.sort();
+        assert_eq!(visited, vec![3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn common_ancestor() {
+        let tree = test_tree();
+        assert_eq!(tree.common_ancestor(&3, &4).unwrap(), 1);
+        assert_eq!(tree.common_ancestor(&3, &5).unwrap(), 0);
+        assert_eq!(tree.common_ancestor(&2, &6).unwrap(), 2);
+        assert_eq!(tree.common_ancestor(&3, &3).unwrap(), 3);
+        assert!(tree.common_ancestor(&3, &8).is_none());
+    }
+
+    #[test]
+    fn path() {
+        let tree = test_tree();
+        assert_eq!(tree.path(&3, &4).unwrap(), vec![3, 1, 4]);
+        assert_eq!(tree.path(&3, &5).unwrap(), vec![3, 1, 0, 2, 5]);
+        assert_eq!(tree.path(&3, &3).unwrap(), vec![3]);
+        assert!(tree.path(&3, &8).is_none());
+    }
+
+    #[test]
+    fn try_insert_duplicate() {
+        let mut tree = test_tree();
+        assert_eq!(tree.try_insert(&3, &0), Err(TreeError::DuplicateNode(3)));
+    }
+
+    #[test]
+    fn try_insert_missing_parent() {
+        let mut tree = test_tree();
+        assert_eq!(tree.try_insert(&8, &42), Err(TreeError::MissingParent(42)));
+    }
+
+    #[test]
+    fn try_insert_ok() {
+        let mut tree = test_tree();
+        assert_eq!(tree.try_insert(&8, &3), Ok(()));
+        assert!(tree.contains(&8));
+    }
+
+    #[test]
+    fn try_reset_root_duplicate() {
+        let mut tree = test_tree();
+        assert_eq!(tree.try_reset_root(&3), Err(TreeError::DuplicateNode(3)));
+    }
+
+    #[test]
+    fn move_subtree() {
+        let mut tree = test_tree();
+        assert_eq!(tree.move_subtree(&2, &1), Ok(()));
+        assert_eq!(*tree.parent(&2).unwrap(), 1);
+        assert!(tree.children(&0).unwrap().iter().all(|child| *child != 2));
+        assert!(tree.children(&1).unwrap().contains(&2));
+        assert!(tree.contains(&5));
+        assert_eq!(*tree.parent(&5).unwrap(), 2);
+    }
+
+    #[test]
+    fn move_subtree_rejects_cycle() {
+        let mut tree = test_tree();
+        assert_eq!(
+            tree.move_subtree(&1, &3),
+            Err(TreeError::WouldCreateCycle(1))
+        );
+        assert_eq!(
+            tree.move_subtree(&1, &1),
+            Err(TreeError::WouldCreateCycle(1))
+        );
+    }
+
+    #[test]
+    fn move_subtree_missing_node() {
+        let mut tree = test_tree();
+        assert_eq!(tree.move_subtree(&8, &1), Err(TreeError::NotInTree(8)));
+        assert_eq!(tree.move_subtree(&1, &8), Err(TreeError::NotInTree(8)));
+    }
+
+    fn weighted_tree() -> MappedTree<i32, i64> {
+        let mut tree = MappedTree::new();
+        tree.reset_root(&0);
+        tree.set_weight(&0, 1);
+
+        tree.insert_weighted(&1, &0, 2);
+        tree.insert_weighted(&2, &0, 3);
+        tree.insert_weighted(&3, &1, 4);
+
+        tree
+    }
+
+    #[test]
+    fn subtree_weight_aggregates_up_the_tree() {
+        let tree = weighted_tree();
+        assert_eq!(tree.subtree_weight(&3), Some(4));
+        assert_eq!(tree.subtree_weight(&1), Some(6));
+        assert_eq!(tree.subtree_weight(&2), Some(3));
+        assert_eq!(tree.subtree_weight(&0), Some(10));
+    }
+
+    #[test]
+    fn set_weight_updates_ancestors() {
+        let mut tree = weighted_tree();
+        tree.set_weight(&3, 10);
+        assert_eq!(tree.subtree_weight(&3), Some(10));
+        assert_eq!(tree.subtree_weight(&1), Some(12));
+        assert_eq!(tree.subtree_weight(&0), Some(16));
+    }
+
+    #[test]
+    fn remove_subtracts_from_ancestors() {
+        let mut tree = weighted_tree();
+        tree.remove(&3);
+        assert_eq!(tree.subtree_weight(&1), Some(2));
+        assert_eq!(tree.subtree_weight(&0), Some(6));
+    }
+
+    #[test]
+    fn remove_children_subtracts_from_ancestors() {
+        let mut tree = weighted_tree();
+        tree.remove_children(&1);
+        assert_eq!(tree.subtree_weight(&1), Some(2));
+        assert_eq!(tree.subtree_weight(&0), Some(6));
+    }
+
+    #[test]
+    fn unsigned_weight_does_not_panic_on_removal() {
+        let mut tree: MappedTree<i32, u32> = MappedTree::new();
+        tree.reset_root(&0);
+        tree.insert_weighted(&1, &0, 5);
+        tree.insert_weighted(&2, &1, 3);
+
+        tree.set_weight(&2, 1);
+        assert_eq!(tree.subtree_weight(&1), Some(6));
+        assert_eq!(tree.subtree_weight(&0), Some(6));
+
+        tree.remove(&2);
+        assert_eq!(tree.subtree_weight(&1), Some(5));
+        assert_eq!(tree.subtree_weight(&0), Some(5));
+
+        tree.remove_children(&0);
+        assert_eq!(tree.subtree_weight(&0), Some(0));
+    }
+
+    #[test]
+    fn move_subtree_updates_weighted_aggregates() {
+        let mut tree = weighted_tree();
+        assert_eq!(tree.move_subtree(&3, &2), Ok(()));
+        assert_eq!(tree.subtree_weight(&3), Some(4));
+        assert_eq!(tree.subtree_weight(&1), Some(2));
+        assert_eq!(tree.subtree_weight(&2), Some(7));
+        assert_eq!(tree.subtree_weight(&0), Some(10));
+    }
+
+    #[test]
+    fn builder_with_root() {
+        let tree: MappedTree<i32> = MappedTreeBuilder::new().with_root(0).build();
+        assert_eq!(tree.len(), 1);
+        assert!(tree.contains(&0));
+        assert_eq!(tree.root(), Some(0));
+    }
+
+    #[test]
+    fn builder_with_capacity_reserves_without_inserting() {
+        let tree: MappedTree<i32> = MappedTreeBuilder::new().with_capacity(16).build();
+        assert!(tree.links_by_obj.capacity() >= 16);
+        assert_eq!(tree.len(), 0);
+        assert!(tree.root().is_none());
+    }
+
+    #[test]
+    fn builder_default_is_empty() {
+        let tree: MappedTree<i32> = MappedTreeBuilder::default().build();
+        assert_eq!(tree.len(), 0);
+    }
+
+    #[test]
+    fn builder_supports_weighted_trees() {
+        let mut tree: MappedTree<i32, i64> =
+            MappedTreeBuilder::new().with_root(0).build();
+        tree.insert_weighted(&1, &0, 5);
+        assert_eq!(tree.subtree_weight(&0), Some(5));
+    }
 }